@@ -2,41 +2,186 @@ extern crate proc_macro;
 
 use proc_macro::TokenStream;
 use quote::quote;
+use syn::parse::{Parse, ParseStream};
 use syn::Data;
 use syn::DeriveInput;
 use syn::{
-    punctuated::Punctuated, token::Comma, Attribute, Field, Fields, FieldsNamed, Ident, Lit, Meta,
-    NestedMeta,
+    punctuated::Punctuated, token::Comma, Attribute, Field, Fields, FieldsNamed, Ident, Lit,
+    LitStr, Meta, NestedMeta, Token, Type, TypePath,
 };
 
 struct FieldArgs {
     env: Option<Lit>,
     default: Option<Lit>,
+    sep: Option<Lit>,
+    concat: Option<Vec<ConcatPart>>,
+    nested: bool,
 }
 
 struct FieldInit {
     name: Ident,
+    ty: Type,
     args: FieldArgs,
+    is_option: bool,
+    is_vec: bool,
 }
 
+/// One part of a `concat = [...]` attribute: either a literal string inserted
+/// verbatim, or an `env "NAME"` lookup with an optional `default "VALUE"` fallback.
+enum ConcatPart {
+    Literal(LitStr),
+    Env { name: LitStr, default: Option<LitStr> },
+}
+
+impl Parse for ConcatPart {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        if input.peek(Ident) {
+            let kw: Ident = input.parse()?;
+            if kw != "env" {
+                return Err(syn::Error::new(kw.span(), "expected `env`"));
+            }
+
+            let name: LitStr = input.parse()?;
+            let default = if input.peek(Ident) {
+                let kw: Ident = input.parse()?;
+                if kw != "default" {
+                    return Err(syn::Error::new(kw.span(), "expected `default`"));
+                }
+                Some(input.parse()?)
+            } else {
+                None
+            };
+
+            Ok(ConcatPart::Env { name, default })
+        } else {
+            Ok(ConcatPart::Literal(input.parse()?))
+        }
+    }
+}
+
+/// The parsed contents of a `#[conf(concat = [...])]` attribute.
+struct ConcatAttr {
+    parts: Punctuated<ConcatPart, Comma>,
+}
+
+impl Parse for ConcatAttr {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let ident: Ident = input.parse()?;
+        if ident != "concat" {
+            return Err(syn::Error::new(ident.span(), "expected `concat`"));
+        }
+
+        input.parse::<Token![=]>()?;
+        let content;
+        syn::bracketed!(content in input);
+        let parts = Punctuated::parse_terminated(&content)?;
+
+        Ok(ConcatAttr { parts })
+    }
+}
+
+/// Returns true if `ty` is `Option<T>`, matched via its type path so that
+/// `Option`, `std::option::Option` and `core::option::Option` are all recognized.
+fn is_option_type(ty: &Type) -> bool {
+    match type_path_idents(ty) {
+        Some(idents) => {
+            let idents: Vec<&str> = idents.iter().map(String::as_str).collect();
+            matches!(
+                idents.as_slice(),
+                ["Option"] | ["std", "option", "Option"] | ["core", "option", "Option"]
+            )
+        }
+        None => false,
+    }
+}
+
+/// Returns true if `ty` is `Vec<T>`, matched via its type path so that
+/// `Vec`, `std::vec::Vec` and `alloc::vec::Vec` are all recognized.
+fn is_vec_type(ty: &Type) -> bool {
+    match type_path_idents(ty) {
+        Some(idents) => {
+            let idents: Vec<&str> = idents.iter().map(String::as_str).collect();
+            matches!(
+                idents.as_slice(),
+                ["Vec"] | ["std", "vec", "Vec"] | ["alloc", "vec", "Vec"]
+            )
+        }
+        None => false,
+    }
+}
+
+fn type_path_idents(ty: &Type) -> Option<Vec<String>> {
+    match ty {
+        Type::Path(TypePath { qself: None, path }) => {
+            Some(path.segments.iter().map(|s| s.ident.to_string()).collect())
+        }
+        _ => None,
+    }
+}
+
+/// Prepends `prefix` to a string literal, preserving its span. Non-string literals
+/// and literals with no prefix configured are returned unchanged.
+fn prefixed_lit(lit: Lit, prefix: &Option<String>) -> Lit {
+    match (prefix, lit) {
+        (Some(p), Lit::Str(s)) => Lit::Str(LitStr::new(&format!("{}{}", p, s.value()), s.span())),
+        (_, lit) => lit,
+    }
+}
+
+fn prefixed_lit_str(lit: LitStr, prefix: &Option<String>) -> LitStr {
+    match prefix {
+        Some(p) => LitStr::new(&format!("{}{}", p, lit.value()), lit.span()),
+        None => lit,
+    }
+}
+
+/// Reads the struct-level `#[conf(prefix = "...")]` attribute, if present.
+fn parse_struct_prefix(attrs: &[Attribute]) -> Option<String> {
+    for attr in attrs {
+        if !attr.path.is_ident("conf") {
+            continue;
+        }
+
+        if let Ok(Meta::List(list)) = attr.parse_meta() {
+            for arg in list.nested {
+                if let NestedMeta::Meta(Meta::NameValue(n)) = arg {
+                    if n.path.is_ident("prefix") {
+                        if let Lit::Str(s) = n.lit {
+                            return Some(s.value());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// The builders below that fall back to a default for an unset env var take a
+/// `set_defaults` flag: when `true`, a used default is persisted
+/// into the process environment via `std::env::set_var` before being parsed, so a
+/// later call observes the same value the field was just initialized with.
 impl FieldInit {
-    fn parse_env_and_default(&self, v: &Lit, d: &Lit) -> quote::__private::TokenStream {
+    /// Resolves `v` from the environment, falling back to `d` when unset.
+    fn parse_env_and_default(&self, v: &Lit, d: &Lit, set_defaults: bool) -> quote::__private::TokenStream {
         let name = self.name.clone();
+        let set_default_var = set_defaults.then(|| quote! { std::env::set_var(#v, &r); });
 
         quote! {
-            #name: if let Ok(r) = std::env::var(#v) {
+            if let Ok(r) = std::env::var(#v) {
                 r.parse().map_err(|_| Error::EnvParse(#v, r))?
             } else {
-                #d.to_string().parse().map_err(|_| Error::DefaultParse(stringify!(#name), stringify!(#d)))?
+                let r = #d.to_string();
+                #set_default_var
+                r.parse().map_err(|_| Error::DefaultParse(stringify!(#name), stringify!(#d)))?
             }
         }
     }
 
     fn parse_env_only(&self, v: &Lit) -> quote::__private::TokenStream {
-        let name = self.name.clone();
-
         quote! {
-            #name: if let Ok(r) = std::env::var(#v) {
+            if let Ok(r) = std::env::var(#v) {
                 r.parse().map_err(|_| Error::EnvParse(#v, r))?
             } else {
                 return Err(Error::MissingEnv(#v));
@@ -47,15 +192,177 @@ impl FieldInit {
     fn parse_default_only(&self, d: &Lit) -> quote::__private::TokenStream {
         let name = self.name.clone();
 
-        quote! { #name: #d.to_string().parse().map_err(|_| Error::DefaultParse(stringify!(#name), stringify!(#d)))? }
+        quote! { #d.to_string().parse().map_err(|_| Error::DefaultParse(stringify!(#name), stringify!(#d)))? }
     }
-}
 
-impl quote::ToTokens for FieldInit {
-    fn to_tokens(&self, tokens: &mut quote::__private::TokenStream) {
-        let gen = if let Some(v) = &self.args.env {
+    fn parse_option_env(&self, v: &Lit) -> quote::__private::TokenStream {
+        quote! {
+            if let Ok(r) = std::env::var(#v) {
+                Some(r.parse().map_err(|_| Error::EnvParse(#v, r))?)
+            } else {
+                None
+            }
+        }
+    }
+
+    fn parse_nested(&self) -> quote::__private::TokenStream {
+        let ty = &self.ty;
+        quote! { <#ty as Setting>::init()? }
+    }
+
+    fn parse_nested_set(&self) -> quote::__private::TokenStream {
+        let ty = &self.ty;
+        quote! { <#ty as Setting>::init_and_set_defaults()? }
+    }
+
+    /// Builds the field expression for a `concat = [...]` attribute.
+    fn parse_concat(&self, parts: &[ConcatPart], set_defaults: bool) -> quote::__private::TokenStream {
+        let name = self.name.clone();
+
+        let pushes: Vec<quote::__private::TokenStream> = parts
+            .iter()
+            .map(|part| match part {
+                ConcatPart::Literal(lit) => quote! { value.push_str(#lit); },
+                ConcatPart::Env { name, default: None } => quote! {
+                    match std::env::var(#name) {
+                        Ok(v) => value.push_str(&v),
+                        Err(_) => return Err(Error::MissingEnv(#name)),
+                    }
+                },
+                ConcatPart::Env {
+                    name,
+                    default: Some(d),
+                } => {
+                    let set_default_var = set_defaults.then(|| quote! { std::env::set_var(#name, #d); });
+                    quote! {
+                        match std::env::var(#name) {
+                            Ok(v) => value.push_str(&v),
+                            Err(_) => {
+                                #set_default_var
+                                value.push_str(#d);
+                            }
+                        }
+                    }
+                }
+            })
+            .collect();
+
+        quote! {
+            {
+                let mut value = String::new();
+                #(#pushes)*
+                value.parse().map_err(|_| Error::EnvParse(stringify!(#name), value))?
+            }
+        }
+    }
+
+    fn separator(&self) -> quote::__private::TokenStream {
+        match &self.args.sep {
+            Some(s) => quote! { #s },
+            None => quote! { "," },
+        }
+    }
+
+    /// Resolves `v` from the environment as a separated collection, falling back to `d`
+    /// when unset.
+    fn parse_vec_env_and_default(&self, v: &Lit, d: &Lit, set_defaults: bool) -> quote::__private::TokenStream {
+        let sep = self.separator();
+        let set_default_var = set_defaults.then(|| quote! { std::env::set_var(#v, &r); });
+
+        quote! {
+            {
+                let raw = if let Ok(r) = std::env::var(#v) {
+                    r
+                } else {
+                    let r = #d.to_string();
+                    #set_default_var
+                    r
+                };
+                let mut values = Vec::new();
+                if !raw.is_empty() {
+                    for part in raw.split(#sep) {
+                        let part = part.trim();
+                        values.push(part.parse().map_err(|_| Error::EnvParse(#v, part.to_string()))?);
+                    }
+                }
+                values
+            }
+        }
+    }
+
+    fn parse_vec_env_only(&self, v: &Lit) -> quote::__private::TokenStream {
+        let sep = self.separator();
+
+        quote! {
+            if let Ok(raw) = std::env::var(#v) {
+                let mut values = Vec::new();
+                if !raw.is_empty() {
+                    for part in raw.split(#sep) {
+                        let part = part.trim();
+                        values.push(part.parse().map_err(|_| Error::EnvParse(#v, part.to_string()))?);
+                    }
+                }
+                values
+            } else {
+                return Err(Error::MissingEnv(#v));
+            }
+        }
+    }
+
+    fn parse_vec_default_only(&self, d: &Lit) -> quote::__private::TokenStream {
+        let name = self.name.clone();
+        let sep = self.separator();
+
+        quote! {
+            {
+                let raw = #d.to_string();
+                let mut values = Vec::new();
+                if !raw.is_empty() {
+                    for part in raw.split(#sep) {
+                        let part = part.trim();
+                        values.push(part.parse().map_err(|_| Error::DefaultParse(stringify!(#name), stringify!(#d)))?);
+                    }
+                }
+                values
+            }
+        }
+    }
+
+    /// Builds the resolution expression for this field. When `set_defaults` is `true`,
+    /// a field that falls back to its `default` because the env var is unset persists
+    /// that default into the process environment via `std::env::set_var`; fields with
+    /// a `default` but no `env` name have nothing to set and are unaffected.
+    fn field_tokens(&self, set_defaults: bool) -> quote::__private::TokenStream {
+        if self.args.nested {
+            if set_defaults {
+                self.parse_nested_set()
+            } else {
+                self.parse_nested()
+            }
+        } else if let Some(parts) = &self.args.concat {
+            self.parse_concat(parts, set_defaults)
+        } else if self.is_option {
+            let v = self
+                .args
+                .env
+                .as_ref()
+                .expect("Option<T> fields require the env attribute param");
+            self.parse_option_env(v)
+        } else if self.is_vec {
+            if let Some(v) = &self.args.env {
+                if let Some(d) = &self.args.default {
+                    self.parse_vec_env_and_default(v, d, set_defaults)
+                } else {
+                    self.parse_vec_env_only(v)
+                }
+            } else if let Some(d) = &self.args.default {
+                self.parse_vec_default_only(d)
+            } else {
+                panic!("Either env or default attribute params are required")
+            }
+        } else if let Some(v) = &self.args.env {
             if let Some(d) = &self.args.default {
-                self.parse_env_and_default(v, d)
+                self.parse_env_and_default(v, d, set_defaults)
             } else {
                 self.parse_env_only(v)
             }
@@ -63,30 +370,55 @@ impl quote::ToTokens for FieldInit {
             self.parse_default_only(d)
         } else {
             panic!("Either env or default attribute params are required")
-        };
+        }
+    }
+}
 
-        tokens.extend(gen);
+impl quote::ToTokens for FieldInit {
+    fn to_tokens(&self, tokens: &mut quote::__private::TokenStream) {
+        tokens.extend(self.field_tokens(false));
     }
 }
 
 fn parse_attribute_args(attr: &Attribute) -> FieldArgs {
+    if let Ok(concat_attr) = attr.parse_args::<ConcatAttr>() {
+        return FieldArgs {
+            env: None,
+            default: None,
+            sep: None,
+            concat: Some(concat_attr.parts.into_iter().collect()),
+            nested: false,
+        };
+    }
+
     let mut args = FieldArgs {
         env: None,
         default: None,
+        sep: None,
+        concat: None,
+        nested: false,
     };
 
     match attr.parse_meta().unwrap() {
         Meta::List(list) => {
             for arg in list.nested {
-                if let NestedMeta::Meta(Meta::NameValue(n)) = arg {
-                    let name = n.path.segments.first().unwrap().ident.to_string();
-                    if name == "env" {
-                        args.env = Some(n.lit);
-                    } else if name == "default" {
-                        args.default = Some(n.lit);
-                    } else {
-                        panic!("Invalid attribute argument name {}", name)
+                match arg {
+                    NestedMeta::Meta(Meta::NameValue(n)) => {
+                        let name = n.path.segments.first().unwrap().ident.to_string();
+                        if name == "env" {
+                            args.env = Some(n.lit);
+                        } else if name == "default" {
+                            args.default = Some(n.lit);
+                        } else if name == "sep" {
+                            args.sep = Some(n.lit);
+                        } else {
+                            panic!("Invalid attribute argument name {}", name)
+                        }
+                    }
+                    NestedMeta::Meta(Meta::Path(p)) if p.is_ident("nested") => {
+                        args.nested = true;
                     }
+                    _ => panic!("Couldn't parse attribute arguments"),
                 }
             }
         }
@@ -96,29 +428,95 @@ fn parse_attribute_args(attr: &Attribute) -> FieldArgs {
     args
 }
 
-impl From<&Field> for FieldInit {
-    fn from(f: &Field) -> FieldInit {
-        let args = if let Some(attr) = f.attrs.first() {
+impl FieldInit {
+    fn new(f: &Field, prefix: &Option<String>) -> FieldInit {
+        let mut args = if let Some(attr) = f.attrs.first() {
             parse_attribute_args(attr)
         } else {
             panic!("Struct fields must have the conf attribute")
         };
 
+        args.env = args.env.map(|env| prefixed_lit(env, prefix));
+        if let Some(parts) = &mut args.concat {
+            for part in parts.iter_mut() {
+                if let ConcatPart::Env { name, .. } = part {
+                    *name = prefixed_lit_str(name.clone(), prefix);
+                }
+            }
+        }
+
         FieldInit {
             name: f.ident.clone().unwrap(),
+            ty: f.ty.clone(),
+            is_option: is_option_type(&f.ty),
+            is_vec: is_vec_type(&f.ty),
             args,
         }
     }
 }
 
-fn impl_setting_struct(name: &Ident, fields: &Punctuated<Field, Comma>) -> TokenStream {
-    let init_fields: Vec<FieldInit> = fields.iter().map(|x| x.into()).collect();
+fn impl_setting_struct(
+    name: &Ident,
+    fields: &Punctuated<Field, Comma>,
+    prefix: &Option<String>,
+) -> TokenStream {
+    let init_fields: Vec<FieldInit> = fields.iter().map(|f| FieldInit::new(f, prefix)).collect();
+    let names: Vec<Ident> = init_fields.iter().map(|f| f.name.clone()).collect();
+    let set_default_fields: Vec<quote::__private::TokenStream> = init_fields
+        .iter()
+        .map(|f| f.field_tokens(true))
+        .collect();
 
     let gen = quote! {
+        #[allow(clippy::needless_question_mark)]
         impl Setting for #name {
             fn init<'a>() -> Result<Self, Error<'a>> {
+                let mut __envconf_errors: Vec<Error<'a>> = Vec::new();
+
+                #(
+                    let #names = (|| -> Result<_, Error<'a>> { Ok(#init_fields) })();
+                    let #names = match #names {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            __envconf_errors.push(e);
+                            None
+                        }
+                    };
+                )*
+
+                if __envconf_errors.len() == 1 {
+                    return Err(__envconf_errors.pop().unwrap());
+                } else if !__envconf_errors.is_empty() {
+                    return Err(Error::Multiple(__envconf_errors));
+                }
+
+                Ok(Self {
+                    #(#names: #names.unwrap()),*
+                })
+            }
+
+            fn init_and_set_defaults<'a>() -> Result<Self, Error<'a>> {
+                let mut __envconf_errors: Vec<Error<'a>> = Vec::new();
+
+                #(
+                    let #names = (|| -> Result<_, Error<'a>> { Ok(#set_default_fields) })();
+                    let #names = match #names {
+                        Ok(v) => Some(v),
+                        Err(e) => {
+                            __envconf_errors.push(e);
+                            None
+                        }
+                    };
+                )*
+
+                if __envconf_errors.len() == 1 {
+                    return Err(__envconf_errors.pop().unwrap());
+                } else if !__envconf_errors.is_empty() {
+                    return Err(Error::Multiple(__envconf_errors));
+                }
+
                 Ok(Self {
-                    #(#init_fields),*
+                    #(#names: #names.unwrap()),*
                 })
             }
         }
@@ -131,6 +529,7 @@ fn impl_setting_struct(name: &Ident, fields: &Punctuated<Field, Comma>) -> Token
 pub fn setting_derive(input: TokenStream) -> TokenStream {
     let ast: DeriveInput = syn::parse(input).unwrap();
     let name = ast.ident;
+    let prefix = parse_struct_prefix(&ast.attrs);
     let data = match ast.data {
         Data::Struct(d) => d,
         _ => panic!("Setting must be a struct"),
@@ -143,5 +542,5 @@ pub fn setting_derive(input: TokenStream) -> TokenStream {
         _ => panic!("Struct fields must be named"),
     };
 
-    impl_setting_struct(&name, &fields)
+    impl_setting_struct(&name, &fields, &prefix)
 }